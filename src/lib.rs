@@ -1,12 +1,18 @@
+extern crate rustyline;
+
 use std::io;
-use std::io::prelude::*;
+use std::io::{BufRead, Write};
 use std::string::ToString;
 
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
 
 use std::collections::BTreeMap;
 
+use rustyline::{Config, Editor};
+use rustyline::error::ReadlineError;
+
 #[derive(Debug)]
 pub enum ExecError {
     Other(String),
@@ -40,42 +46,370 @@ impl Error for ExecError {
 
 pub type ExecResult = Result<(), ExecError>;
 
-pub type CmdFn<T> = Fn(&mut T, &[&str]);
+#[derive(PartialEq)]
+enum TokenizeState {
+    Unquoted,
+    SingleQuoted,
+    DoubleQuoted,
+    Escaped(Box<TokenizeState>)
+}
+use TokenizeState::*;
+
+/// Split `line` into shell-style words, honouring single quotes, double quotes
+/// and backslash escapes. Closing a quote does not end the current token, so
+/// `a"b"c` yields a single `abc` token.
+pub fn tokenize(line: &str) -> Result<Vec<String>, ExecError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut has_token = false;
+    let mut state = Unquoted;
+
+    for c in line.chars() {
+        state = match state {
+            Escaped(prev) => {
+                has_token = true;
+                current.push(c);
+                *prev
+            },
+            Unquoted if c == '\\' => Escaped(Box::new(Unquoted)),
+            Unquoted if c == '\'' => { has_token = true; SingleQuoted },
+            Unquoted if c == '"' => { has_token = true; DoubleQuoted },
+            Unquoted if c.is_whitespace() => {
+                if has_token {
+                    tokens.push(current.clone());
+                    current.clear();
+                    has_token = false;
+                }
+                Unquoted
+            },
+            Unquoted => { has_token = true; current.push(c); Unquoted },
+            SingleQuoted if c == '\'' => Unquoted,
+            SingleQuoted => { current.push(c); SingleQuoted },
+            DoubleQuoted if c == '\\' => Escaped(Box::new(DoubleQuoted)),
+            DoubleQuoted if c == '"' => Unquoted,
+            DoubleQuoted => { current.push(c); DoubleQuoted }
+        };
+    }
+
+    match state {
+        Unquoted => {
+            if has_token {
+                tokens.push(current);
+            }
+            return Ok(tokens);
+        },
+        _ => return Err(Other(String::from("unterminated quote")))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ArgKind {
+    Str,
+    Int,
+    Float,
+    Bool
+}
+
+#[derive(Clone)]
+pub struct ArgSpec {
+    name: String,
+    required: bool,
+    kind: ArgKind,
+    variadic: bool
+}
+
+impl ArgSpec {
+    pub fn new<S: ToString>(name: S, required: bool, kind: ArgKind) -> ArgSpec {
+        return ArgSpec {
+            name: name.to_string(),
+            required: required,
+            kind: kind,
+            variadic: false
+        };
+    }
+
+    /// A trailing spec that swallows every remaining token instead of just one.
+    pub fn variadic<S: ToString>(name: S, required: bool, kind: ArgKind) -> ArgSpec {
+        return ArgSpec {
+            name: name.to_string(),
+            required: required,
+            kind: kind,
+            variadic: true
+        };
+    }
+
+    fn usage_token(&self) -> String {
+        let kind = match self.kind {
+            ArgKind::Str => "str",
+            ArgKind::Int => "int",
+            ArgKind::Float => "float",
+            ArgKind::Bool => "bool"
+        };
+        let token = format!("{}:{}", self.name, kind);
+        return if self.variadic {
+            format!("[{}...]", token)
+        } else if self.required {
+            format!("<{}>", token)
+        } else {
+            format!("[{}]", token)
+        };
+    }
+}
+
+enum ArgValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<String>)
+}
+
+/// Typed, named positional arguments parsed from a command line according to
+/// its `ArgSpec`s. Handlers look values up by the name given at registration.
+pub struct Args {
+    values: BTreeMap<String, ArgValue>
+}
+
+impl Args {
+    fn get(&self, name: &str) -> Result<&ArgValue, ExecError> {
+        return self.values.get(name).ok_or_else(|| Other(format!("no such argument '{}'", name)));
+    }
+
+    pub fn str(&self, name: &str) -> Result<&str, ExecError> {
+        return match try!(self.get(name)) {
+            &ArgValue::Str(ref s) => Ok(s.as_str()),
+            _ => Err(Other(format!("argument '{}' is not a string", name)))
+        };
+    }
+
+    pub fn int(&self, name: &str) -> Result<i64, ExecError> {
+        return match try!(self.get(name)) {
+            &ArgValue::Int(i) => Ok(i),
+            _ => Err(Other(format!("argument '{}' is not an int", name)))
+        };
+    }
+
+    pub fn float(&self, name: &str) -> Result<f64, ExecError> {
+        return match try!(self.get(name)) {
+            &ArgValue::Float(f) => Ok(f),
+            _ => Err(Other(format!("argument '{}' is not a float", name)))
+        };
+    }
+
+    pub fn bool(&self, name: &str) -> Result<bool, ExecError> {
+        return match try!(self.get(name)) {
+            &ArgValue::Bool(b) => Ok(b),
+            _ => Err(Other(format!("argument '{}' is not a bool", name)))
+        };
+    }
+
+    pub fn list(&self, name: &str) -> Result<&[String], ExecError> {
+        return match try!(self.get(name)) {
+            &ArgValue::List(ref v) => Ok(v.as_slice()),
+            _ => Err(Other(format!("argument '{}' is not a list", name)))
+        };
+    }
+
+    /// Like `str`, but a non-required arg that was omitted yields `None`
+    /// instead of an error, so handlers can use `?` on an optional value.
+    pub fn str_opt(&self, name: &str) -> Result<Option<&str>, ExecError> {
+        return match self.values.get(name) {
+            None => Ok(None),
+            Some(&ArgValue::Str(ref s)) => Ok(Some(s.as_str())),
+            Some(_) => Err(Other(format!("argument '{}' is not a string", name)))
+        };
+    }
+
+    /// Like `int`, but a non-required arg that was omitted yields `None`
+    /// instead of an error, so handlers can use `?` on an optional value.
+    pub fn int_opt(&self, name: &str) -> Result<Option<i64>, ExecError> {
+        return match self.values.get(name) {
+            None => Ok(None),
+            Some(&ArgValue::Int(i)) => Ok(Some(i)),
+            Some(_) => Err(Other(format!("argument '{}' is not an int", name)))
+        };
+    }
+
+    /// Like `float`, but a non-required arg that was omitted yields `None`
+    /// instead of an error, so handlers can use `?` on an optional value.
+    pub fn float_opt(&self, name: &str) -> Result<Option<f64>, ExecError> {
+        return match self.values.get(name) {
+            None => Ok(None),
+            Some(&ArgValue::Float(f)) => Ok(Some(f)),
+            Some(_) => Err(Other(format!("argument '{}' is not a float", name)))
+        };
+    }
+
+    /// Like `bool`, but a non-required arg that was omitted yields `None`
+    /// instead of an error, so handlers can use `?` on an optional value.
+    pub fn bool_opt(&self, name: &str) -> Result<Option<bool>, ExecError> {
+        return match self.values.get(name) {
+            None => Ok(None),
+            Some(&ArgValue::Bool(b)) => Ok(Some(b)),
+            Some(_) => Err(Other(format!("argument '{}' is not a bool", name)))
+        };
+    }
+
+    /// Like `list`, but a non-required arg that was omitted yields `None`
+    /// instead of an error, so handlers can use `?` on an optional value.
+    pub fn list_opt(&self, name: &str) -> Result<Option<&[String]>, ExecError> {
+        return match self.values.get(name) {
+            None => Ok(None),
+            Some(&ArgValue::List(ref v)) if v.is_empty() => Ok(None),
+            Some(&ArgValue::List(ref v)) => Ok(Some(v.as_slice())),
+            Some(_) => Err(Other(format!("argument '{}' is not a list", name)))
+        };
+    }
+}
+
+fn parse_value(spec: &ArgSpec, raw: &str) -> Result<ArgValue, ExecError> {
+    return match spec.kind {
+        ArgKind::Str => Ok(ArgValue::Str(raw.to_string())),
+        ArgKind::Int => raw.parse::<i64>().map(ArgValue::Int)
+            .map_err(|_| Other(format!("'{}' is not a valid int for argument '{}'", raw, spec.name))),
+        ArgKind::Float => raw.parse::<f64>().map(ArgValue::Float)
+            .map_err(|_| Other(format!("'{}' is not a valid float for argument '{}'", raw, spec.name))),
+        ArgKind::Bool => match raw {
+            "true" | "1" => Ok(ArgValue::Bool(true)),
+            "false" | "0" => Ok(ArgValue::Bool(false)),
+            _ => Err(Other(format!("'{}' is not a valid bool for argument '{}'", raw, spec.name)))
+        }
+    };
+}
+
+fn parse_args(specs: &[ArgSpec], raw_args: &[&str]) -> Result<Args, ExecError> {
+    let mut values = BTreeMap::new();
+    let mut idx = 0;
+
+    for (i, spec) in specs.iter().enumerate() {
+        if spec.variadic && i == specs.len() - 1 {
+            if spec.required && idx >= raw_args.len() {
+                return Err(MissingArgs);
+            }
+            let rest = raw_args[idx..].iter().map(|s| s.to_string()).collect();
+            values.insert(spec.name.clone(), ArgValue::List(rest));
+            idx = raw_args.len();
+            continue;
+        }
+
+        if idx >= raw_args.len() {
+            if spec.required {
+                return Err(MissingArgs);
+            }
+            continue;
+        }
+
+        let value = try!(parse_value(spec, raw_args[idx]));
+        values.insert(spec.name.clone(), value);
+        idx += 1;
+    }
+
+    return Ok(Args { values: values });
+}
+
+pub type CmdFn<T> = Fn(&mut T, &Args) -> ExecResult;
 
 pub struct Command<T> {
     name: String,
     description: String,
-    nargs: usize,
-    func: Box<CmdFn<T>>
+    args: Vec<ArgSpec>,
+    func: Option<Box<CmdFn<T>>>,
+    children: BTreeMap<String, Command<T>>
 }
 
 impl <T> Command<T> {
-    pub fn new(name: String, description: String, nargs: usize, func: Box<CmdFn<T>>) -> Command<T> {
+    pub fn new(name: String, description: String, args: Vec<ArgSpec>, func: Box<CmdFn<T>>) -> Command<T> {
+        return Command {
+            name: name,
+            description: description,
+            args: args,
+            func: Some(func),
+            children: BTreeMap::new()
+        };
+    }
+
+    /// Create a command group: a named node with no handler of its own whose
+    /// children are resolved by walking the remaining tokens.
+    pub fn new_group(name: String, description: String) -> Command<T> {
         return Command {
             name: name,
             description: description,
-            nargs: nargs,
-            func: func
+            args: Vec::new(),
+            func: None,
+            children: BTreeMap::new()
         };
     }
 
-    pub fn help(&self) {
-        println!("{} :\t{}", self.name, self.description);
+    pub fn add_child(&mut self, cmd: Command<T>) {
+        self.children.insert(cmd.name.clone(), cmd);
+    }
+
+    /// Build and register a leaf command under this group, returning it for chaining.
+    pub fn new_child<S: ToString, F: Fn(&mut T, &Args) -> ExecResult + 'static>(&mut self, name: S, description: S, args: Vec<ArgSpec>, func: F) -> &mut Command<T> {
+        self.add_child(Command::new(name.to_string(), description.to_string(), args, Box::new(func)));
+        return self.children.get_mut(&name.to_string()).unwrap();
     }
 
-    pub fn run(&self, value: &mut T, args: &[&str]) -> ExecResult {
-        if args.len() < self.nargs {
-            return Err(MissingArgs);
+    /// Build and register a nested command group under this group, returning it for chaining.
+    pub fn new_child_group<S: ToString>(&mut self, name: S, description: S) -> &mut Command<T> {
+        self.add_child(Command::new_group(name.to_string(), description.to_string()));
+        return self.children.get_mut(&name.to_string()).unwrap();
+    }
+
+    fn usage(&self) -> Option<String> {
+        if self.args.is_empty() {
+            return None;
         }
-        (self.func)(value, args);
-        return Ok(());
+        let tokens: Vec<String> = self.args.iter().map(ArgSpec::usage_token).collect();
+        return Some(format!("Usage: {} {}", self.name, tokens.join(" ")));
+    }
+
+    fn help_tree<W: Write>(&self, writer: &mut W, depth: usize) {
+        let indent = "  ".repeat(depth);
+        writeln!(writer, "{}{} :\t{}", indent, self.name, self.description).unwrap();
+        if let Some(usage) = self.usage() {
+            writeln!(writer, "{}  {}", indent, usage).unwrap();
+        }
+        for child in self.children.values() {
+            child.help_tree(writer, depth + 1);
+        }
+    }
+
+    pub fn help<W: Write>(&self, writer: &mut W) {
+        self.help_tree(writer, 0);
+    }
+
+    pub fn run(&self, value: &mut T, raw_args: &[&str]) -> ExecResult {
+        if !self.children.is_empty() {
+            return match raw_args.first() {
+                None => Err(MissingArgs),
+                Some(next) => match self.children.get(*next) {
+                    None => Err(UnknownCommand(format!("{} {}", self.name, next))),
+                    Some(child) => match child.run(value, &raw_args[1..]) {
+                        Err(UnknownCommand(deeper)) => Err(UnknownCommand(format!("{} {}", self.name, deeper))),
+                        other => other
+                    }
+                }
+            };
+        }
+
+        let func = match self.func {
+            Some(ref f) => f,
+            None => return Err(Other(String::from("command requires a subcommand")))
+        };
+
+        let args = try!(parse_args(&self.args, raw_args));
+        return func(value, &args);
     }
 }
 
 pub struct Shell<T> {
     commands: BTreeMap<String, Command<T>>,
     value: T,
-    prompt: String
+    prompt: String,
+    history_file: Option<PathBuf>,
+    history_limit: usize,
+    continue_on_error: bool
 }
 
 impl <T> Shell<T> {
@@ -83,7 +417,10 @@ impl <T> Shell<T> {
         return Shell {
             commands: BTreeMap::new(),
             value: value,
-            prompt: String::from(">")
+            prompt: String::from(">"),
+            history_file: None,
+            history_limit: 1000,
+            continue_on_error: false
         };
     }
 
@@ -91,51 +428,365 @@ impl <T> Shell<T> {
         self.prompt = prompt;
     }
 
+    /// Load/save readline history to and from `path` across `run_loop` invocations.
+    pub fn set_history_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.history_file = Some(path.into());
+    }
+
+    /// Bound the number of entries kept in the in-memory history ring.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+    }
+
+    /// When true, `run_script` logs a failing line's error and keeps going
+    /// instead of stopping at the first one.
+    pub fn set_continue_on_error(&mut self, continue_on_error: bool) {
+        self.continue_on_error = continue_on_error;
+    }
+
     pub fn register_command(&mut self, cmd: Command<T>) {
         self.commands.insert(cmd.name.clone(), cmd);
     }
 
-    pub fn new_command<S: ToString, F: Fn(&mut T, &[&str]) + 'static>(&mut self, name: S, description: S, nargs: usize, func: F) {
-        self.register_command(Command::new(name.to_string(), description.to_string(), nargs, Box::new(func)));
+    pub fn new_command<S: ToString, F: Fn(&mut T, &Args) -> ExecResult + 'static>(&mut self, name: S, description: S, args: Vec<ArgSpec>, func: F) {
+        self.register_command(Command::new(name.to_string(), description.to_string(), args, Box::new(func)));
+    }
+
+    /// Register a command group (e.g. `git`) and return it so subcommands can
+    /// be attached with `Command::new_child`/`new_child_group`.
+    pub fn new_group<S: ToString>(&mut self, name: S, description: S) -> &mut Command<T> {
+        self.register_command(Command::new_group(name.to_string(), description.to_string()));
+        return self.commands.get_mut(&name.to_string()).unwrap();
     }
 
-    pub fn help(&self) -> ExecResult {
+    pub fn help<W: Write>(&self, writer: &mut W) -> ExecResult {
         for cmd in self.commands.values() {
-            cmd.help();
+            cmd.help(writer);
         }
         return Ok(());
     }
 
-    pub fn run(&mut self, line: &str) -> ExecResult {
-        let mut splt = line.trim().split_whitespace();
+    pub fn run<W: Write>(&mut self, line: &str, writer: &mut W) -> ExecResult {
+        let tokens = try!(tokenize(line.trim()));
+        let mut splt = tokens.iter();
         return match splt.next() {
             None => Ok(()),
-            Some("help") => self.help(),
-            Some("quit") => Err(Quit),
-            Some(cmd) => match self.commands.get(cmd) {
-                None => Err(UnknownCommand(cmd.to_string())),
-                Some(c) => c.run(&mut self.value, &splt.collect::<Vec<&str>>())
+            Some(cmd) if cmd.as_str() == "help" => self.help(writer),
+            Some(cmd) if cmd.as_str() == "quit" => Err(Quit),
+            Some(cmd) => match self.commands.get(cmd.as_str()) {
+                None => Err(UnknownCommand(cmd.clone())),
+                Some(c) => c.run(&mut self.value, &splt.map(|s| s.as_str()).collect::<Vec<&str>>())
             }
         };
     }
 
-    fn print_prompt(&self) {
-        let mut stdout = io::stdout();
-        write!(stdout, "{}", self.prompt).unwrap();
-        stdout.flush().unwrap();
+    fn push_history(&self, editor: &mut Editor<()>, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let is_dup = editor.history().iter().next_back().map_or(false, |last| last == line);
+        if !is_dup {
+            editor.history_mut().add(line);
+        }
+    }
+
+    fn flush_history(&self, editor: &mut Editor<()>) {
+        if let Some(ref path) = self.history_file {
+            let _ = editor.save_history(path);
+        }
     }
 
     pub fn run_loop(&mut self) {
+        let config = Config::builder().max_history_size(self.history_limit).build();
+        let mut editor: Editor<()> = Editor::with_config(config);
+        if let Some(ref path) = self.history_file {
+            let _ = editor.load_history(path);
+        }
+
+        let mut stdout = io::stdout();
+        loop {
+            match editor.readline(&self.prompt) {
+                Ok(line) => {
+                    self.push_history(&mut editor, &line);
+                    if let Err(e) = self.run(&line, &mut stdout) {
+                        match e {
+                            Quit => break,
+                            e @ _ => println!("{}", e)
+                        };
+                    }
+                },
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(_) => break
+            }
+        }
+
+        self.flush_history(&mut editor);
+    }
+
+    /// Drive the shell from an arbitrary reader/writer pair instead of an
+    /// interactive terminal, printing the prompt and writing output through
+    /// `writer` and reading commands line-by-line from `reader`. This is what
+    /// makes the shell testable (feed a `&[u8]`, capture a `Vec<u8>`) and usable
+    /// over a plain socket, unlike `run_loop` which always reads the process's
+    /// stdin through rustyline.
+    pub fn run_with<R: BufRead, W: Write>(&mut self, mut reader: R, mut writer: W) -> ExecResult {
+        loop {
+            try!(write!(writer, "{}", self.prompt).map_err(|e| Other(e.to_string())));
+            try!(writer.flush().map_err(|e| Other(e.to_string())));
+
+            let mut line = String::new();
+            let bytes_read = try!(reader.read_line(&mut line).map_err(|e| Other(e.to_string())));
+            if bytes_read == 0 {
+                return Ok(());
+            }
+
+            if let Err(e) = self.run(&line, &mut writer) {
+                match e {
+                    Quit => return Ok(()),
+                    e @ _ => try!(writeln!(writer, "{}", e).map_err(|e| Other(e.to_string())))
+                };
+            }
+        }
+    }
+
+    /// Convenience wrapper around `run_with` for the common case of driving
+    /// the shell from the process's own stdin/stdout.
+    pub fn run_with_stdio(&mut self) -> ExecResult {
         let stdin = io::stdin();
-        self.print_prompt();
-        for line in stdin.lock().lines().map(|l| l.unwrap()) {
-            if let Err(e) =  self.run(&line) {
+        return self.run_with(stdin.lock(), io::stdout());
+    }
+
+    /// Execute commands from `reader` one line at a time with no interactive
+    /// prompt, e.g. to load an init file before `run_loop`, writing any output
+    /// through `writer`. Blank lines and lines starting with `#` are skipped.
+    /// Stops at the first failing line unless `continue_on_error` is set, in
+    /// which case the error is written and execution continues.
+    pub fn run_script<R: BufRead, W: Write>(&mut self, reader: R, mut writer: W) -> ExecResult {
+        for line in reader.lines() {
+            let line = try!(line.map_err(|e| Other(e.to_string())));
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            if let Err(e) = self.run(&line, &mut writer) {
                 match e {
-                    Quit => return,
-                    e @ _ => println!("{}", e)
+                    Quit => return Ok(()),
+                    e @ _ => {
+                        if self.continue_on_error {
+                            try!(writeln!(writer, "{}", e).map_err(|e| Other(e.to_string())));
+                        } else {
+                            return Err(e);
+                        }
+                    }
                 };
             }
-            self.print_prompt();
         }
+        return Ok(());
+    }
+
+    /// Convenience wrapper around `run_script` for the common case of loading
+    /// a script file while writing output to the process's own stdout.
+    pub fn run_script_stdio<R: BufRead>(&mut self, reader: R) -> ExecResult {
+        return self.run_script(reader, io::stdout());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn run_with_writes_command_output_through_the_writer() {
+        let mut shell: Shell<()> = Shell::new(());
+        shell.new_command("greet", "say hello", Vec::new(), |_: &mut (), _: &Args| Ok(()));
+
+        let reader = Cursor::new(&b"help\nquit\n"[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_with(reader, &mut writer);
+
+        assert!(result.is_ok());
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.contains("greet"));
+        assert!(output.contains("say hello"));
+    }
+
+    #[test]
+    fn run_with_stops_on_quit() {
+        let mut shell: Shell<()> = Shell::new(());
+        let reader = Cursor::new(&b"quit\nhelp\n"[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_with(reader, &mut writer);
+
+        assert!(result.is_ok());
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output.matches('>').count(), 1);
+    }
+
+    #[test]
+    fn run_with_stops_cleanly_at_eof() {
+        let mut shell: Shell<()> = Shell::new(());
+        let reader = Cursor::new(&b""[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_with(reader, &mut writer);
+
+        assert!(result.is_ok());
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output, ">");
+    }
+
+    #[test]
+    fn run_script_skips_comments_and_blank_lines() {
+        let mut shell: Shell<u32> = Shell::new(0);
+        shell.new_command("tick", "increments the counter", Vec::new(), |state: &mut u32, _: &Args| { *state += 1; Ok(()) });
+
+        let reader = Cursor::new(&b"# a comment\n\ntick\n"[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_script(reader, &mut writer);
+
+        assert!(result.is_ok());
+        assert_eq!(shell.value, 1);
+    }
+
+    #[test]
+    fn run_script_stops_at_the_first_error_by_default() {
+        let mut shell: Shell<u32> = Shell::new(0);
+        shell.new_command("fail", "always fails", Vec::new(), |_: &mut u32, _: &Args| Err(Other(String::from("boom"))));
+        shell.new_command("tick", "increments the counter", Vec::new(), |state: &mut u32, _: &Args| { *state += 1; Ok(()) });
+
+        let reader = Cursor::new(&b"fail\ntick\n"[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_script(reader, &mut writer);
+
+        assert!(result.is_err());
+        assert_eq!(shell.value, 0);
+    }
+
+    #[test]
+    fn run_script_with_continue_on_error_writes_and_keeps_going() {
+        let mut shell: Shell<u32> = Shell::new(0);
+        shell.set_continue_on_error(true);
+        shell.new_command("fail", "always fails", Vec::new(), |_: &mut u32, _: &Args| Err(Other(String::from("boom"))));
+        shell.new_command("tick", "increments the counter", Vec::new(), |state: &mut u32, _: &Args| { *state += 1; Ok(()) });
+
+        let reader = Cursor::new(&b"fail\ntick\n"[..]);
+        let mut writer = Vec::new();
+        let result = shell.run_script(reader, &mut writer);
+
+        assert!(result.is_ok());
+        assert_eq!(shell.value, 1);
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.contains("boom"));
+    }
+
+    #[test]
+    fn tokenize_splits_on_whitespace() {
+        let tokens = tokenize("foo bar  baz").unwrap();
+        assert_eq!(tokens, vec!["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn tokenize_closing_a_quote_does_not_end_the_token() {
+        let tokens = tokenize("a\"b\"c").unwrap();
+        assert_eq!(tokens, vec!["abc"]);
+    }
+
+    #[test]
+    fn tokenize_single_quotes_do_not_interpret_escapes() {
+        let tokens = tokenize("'a\\nb'").unwrap();
+        assert_eq!(tokens, vec!["a\\nb"]);
+    }
+
+    #[test]
+    fn tokenize_double_quotes_interpret_escapes() {
+        let tokens = tokenize("\"a\\\"b\"").unwrap();
+        assert_eq!(tokens, vec!["a\"b"]);
+    }
+
+    #[test]
+    fn tokenize_unterminated_quote_is_an_error() {
+        match tokenize("'unterminated") {
+            Err(Other(_)) => {},
+            other => panic!("expected Err(Other(_)), got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_args_fills_in_named_typed_values() {
+        let specs = vec![
+            ArgSpec::new("name", true, ArgKind::Str),
+            ArgSpec::new("count", true, ArgKind::Int)
+        ];
+        let args = parse_args(&specs, &["alice", "3"]).unwrap();
+        assert_eq!(args.str("name").unwrap(), "alice");
+        assert_eq!(args.int("count").unwrap(), 3);
+    }
+
+    #[test]
+    fn parse_args_missing_required_arg_is_an_error() {
+        let specs = vec![ArgSpec::new("name", true, ArgKind::Str)];
+        match parse_args(&specs, &[]) {
+            Err(MissingArgs) => {},
+            other => panic!("expected Err(MissingArgs), got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn parse_args_optional_arg_may_be_omitted() {
+        let specs = vec![ArgSpec::new("verbose", false, ArgKind::Bool)];
+        let args = parse_args(&specs, &[]).unwrap();
+        match args.bool("verbose") {
+            Err(Other(_)) => {},
+            other => panic!("expected Err(Other(_)) for an unset optional arg, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn args_opt_accessor_treats_an_omitted_optional_arg_as_none() {
+        let specs = vec![ArgSpec::new("verbose", false, ArgKind::Bool)];
+        let args = parse_args(&specs, &[]).unwrap();
+        assert_eq!(args.bool_opt("verbose").unwrap(), None);
+    }
+
+    #[test]
+    fn args_opt_accessor_returns_the_value_when_present() {
+        let specs = vec![ArgSpec::new("count", false, ArgKind::Int)];
+        let args = parse_args(&specs, &["5"]).unwrap();
+        assert_eq!(args.int_opt("count").unwrap(), Some(5));
+    }
+
+    #[test]
+    fn args_opt_accessor_still_errors_on_a_type_mismatch() {
+        let specs = vec![ArgSpec::new("count", false, ArgKind::Int)];
+        let args = parse_args(&specs, &["5"]).unwrap();
+        match args.str_opt("count") {
+            Err(Other(_)) => {},
+            other => panic!("expected Err(Other(_)) for a type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_args_rejects_an_invalid_typed_value() {
+        let specs = vec![ArgSpec::new("count", true, ArgKind::Int)];
+        match parse_args(&specs, &["not-a-number"]) {
+            Err(Other(_)) => {},
+            other => panic!("expected Err(Other(_)), got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn parse_args_variadic_spec_swallows_remaining_tokens() {
+        let specs = vec![ArgSpec::variadic("files", true, ArgKind::Str)];
+        let args = parse_args(&specs, &["a", "b", "c"]).unwrap();
+        assert_eq!(args.list("files").unwrap(), &["a", "b", "c"]);
+    }
+
+    #[test]
+    fn arg_spec_usage_token_marks_required_optional_and_variadic() {
+        assert_eq!(ArgSpec::new("name", true, ArgKind::Str).usage_token(), "<name:str>");
+        assert_eq!(ArgSpec::new("name", false, ArgKind::Int).usage_token(), "[name:int]");
+        assert_eq!(ArgSpec::variadic("files", true, ArgKind::Str).usage_token(), "[files:str...]");
     }
 }